@@ -2,11 +2,18 @@
 use std::env;
 use std::collections::HashMap;
 use std::error::Error;
+use std::time::{Duration, SystemTime};
 
 use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 use log::{info, error};
 
+/// Default `User-Agent` sent with every request, derived from the crate name
+/// and version so servers can attribute and rate-limit traffic.
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     id: u32,
@@ -14,28 +21,659 @@ pub struct User {
     email: String,
 }
 
+/// An optional credential attached to every outgoing request.
+#[derive(Clone)]
+pub enum Credential {
+    /// `Authorization: Bearer <token>`.
+    Bearer(String),
+    /// A custom API-key header, e.g. `Hydrus-Client-API-Access-Key: <value>`.
+    ApiKey { header: String, value: String },
+}
+
+/// A user payload for creation, without the server-assigned `id`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewUser {
+    pub name: String,
+    pub email: String,
+}
+
+/// A partial update for an existing user. Unset fields are omitted from the
+/// `PATCH` body so the server leaves them untouched.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UserPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+/// Failure modes of a [`UserService`] request, so callers can match on the
+/// kind of error instead of inspecting stringly-typed messages.
+#[derive(Debug)]
+pub enum UserServiceError {
+    /// The request never completed (connection, timeout, DNS, ...).
+    Transport(reqwest::Error),
+    /// The response body could not be decoded into the expected type.
+    Deserialize(reqwest::Error),
+    /// The server returned `404 Not Found`.
+    NotFound,
+    /// The server returned `401 Unauthorized` or `403 Forbidden`.
+    Unauthorized,
+    /// The server returned a `5xx` status, or any other non-success status
+    /// not covered above (e.g. `400`, or a `429` once retries are exhausted).
+    Server(reqwest::StatusCode),
+}
+
+impl std::fmt::Display for UserServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserServiceError::Transport(err) => write!(f, "transport error: {}", err),
+            UserServiceError::Deserialize(err) => write!(f, "failed to deserialize response: {}", err),
+            UserServiceError::NotFound => write!(f, "resource not found"),
+            UserServiceError::Unauthorized => write!(f, "request was not authorized"),
+            UserServiceError::Server(status) => write!(f, "server error: {}", status),
+        }
+    }
+}
+
+impl Error for UserServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UserServiceError::Transport(err) | UserServiceError::Deserialize(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// How a [`UserService`] retries transient failures and rate limits.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the initial attempt.
+    pub max_retries: u32,
+    /// Base delay for the first backoff; doubles each attempt.
+    pub base_delay: Duration,
+    /// Upper bound for the computed backoff; jitter is added on top of it.
+    pub max_delay: Duration,
+    /// Add random jitter (up to the computed delay) to avoid thundering herds.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff for a given zero-based attempt: `base_delay * 2^attempt`, capped
+    /// at `max_delay`, with optional jitter applied on top.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        let raw = self.base_delay.saturating_mul(factor);
+        let capped = raw.min(self.max_delay);
+        if self.jitter {
+            capped.saturating_add(jitter_up_to(capped))
+        } else {
+            capped
+        }
+    }
+}
+
 pub struct UserService {
     client: Client,
     api_url: String,
+    retry_policy: RetryPolicy,
 }
 
 impl UserService {
     pub fn new() -> Self {
-        let api_url = env::var("API_URL").unwrap_or_else(|_| "https://api.example.com".to_string());
-        
+        let mut builder = UserService::builder();
+        if let Ok(token) = env::var("API_TOKEN") {
+            builder = builder.with_token(token);
+        }
+        builder.build()
+    }
+
+    /// Construct a service from an already-configured [`Client`] and base URL.
+    ///
+    /// This is the seam used by [`new`](UserService::new) and the builder, and
+    /// lets tests point the service at a local mock server.
+    pub fn with_client(client: Client, api_url: String) -> Self {
         UserService {
-            client: Client::new(),
+            client,
             api_url,
+            retry_policy: RetryPolicy::default(),
         }
     }
-    
-    pub async fn get_users(&self) -> Result<Vec<User>, Box<dyn Error>> {
+
+    /// Construct a service that authenticates with a bearer token.
+    pub fn with_token(token: String) -> Self {
+        UserService::builder().with_token(token).build()
+    }
+
+    /// Construct a service that authenticates with a custom API-key header.
+    pub fn with_api_key(header_name: &str, value: &str) -> Self {
+        UserService::builder().with_api_key(header_name, value).build()
+    }
+
+    /// Start configuring a [`UserService`] with a custom `User-Agent` and/or
+    /// default headers before building the underlying [`Client`].
+    pub fn builder() -> UserServiceBuilder {
+        UserServiceBuilder::new()
+    }
+
+    pub async fn get_users(&self) -> Result<Vec<User>, UserServiceError> {
         info!("Fetching all users");
-        let response = self.client.get(&format!("{}/users", self.api_url))
-            .send()
-            .await?;
-            
-        let users = response.json::<Vec<User>>().await?;
-        Ok(users)
-    }
-}
\ No newline at end of file
+        let response = self.send_with_retry(self.client.get(&format!("{}/users", self.api_url))).await?;
+
+        let response = check_status(response)?;
+        response.json::<Vec<User>>().await.map_err(UserServiceError::Deserialize)
+    }
+
+    /// Fetch a single user by id (`GET {api_url}/users/{id}`).
+    pub async fn get_user(&self, id: u32) -> Result<User, UserServiceError> {
+        info!("Fetching user {}", id);
+        let response = self.send_with_retry(self.client.get(&format!("{}/users/{}", self.api_url, id))).await?;
+
+        let response = check_status(response)?;
+        response.json::<User>().await.map_err(UserServiceError::Deserialize)
+    }
+
+    /// Create a user (`POST {api_url}/users`) and return the server's record.
+    pub async fn create_user(&self, new_user: NewUser) -> Result<User, UserServiceError> {
+        info!("Creating user {}", new_user.email);
+        let response = self.send_with_retry(
+            self.client.post(&format!("{}/users", self.api_url)).json(&new_user),
+        ).await?;
+
+        let response = check_status(response)?;
+        response.json::<User>().await.map_err(UserServiceError::Deserialize)
+    }
+
+    /// Apply a partial update to a user (`PATCH {api_url}/users/{id}`).
+    pub async fn update_user(&self, id: u32, patch: UserPatch) -> Result<User, UserServiceError> {
+        info!("Updating user {}", id);
+        let response = self.send_with_retry(
+            self.client.patch(&format!("{}/users/{}", self.api_url, id)).json(&patch),
+        ).await?;
+
+        let response = check_status(response)?;
+        response.json::<User>().await.map_err(UserServiceError::Deserialize)
+    }
+
+    /// Delete a user (`DELETE {api_url}/users/{id}`).
+    pub async fn delete_user(&self, id: u32) -> Result<(), UserServiceError> {
+        info!("Deleting user {}", id);
+        let response = self.send_with_retry(self.client.delete(&format!("{}/users/{}", self.api_url, id))).await?;
+
+        check_status(response)?;
+        Ok(())
+    }
+
+    /// Iterate over users one page at a time without loading the whole list.
+    ///
+    /// The returned [`UserStream`] fetches the first page lazily on the first
+    /// call to [`UserStream::next`] and walks subsequent pages by following the
+    /// `rel="next"` URL from the `Link` response header (RFC 5988), falling back
+    /// to a `?page=N&per_page=M` scheme when no such header is present.
+    pub fn users_iter(&self) -> UserStream<'_> {
+        UserStream::new(self, 20)
+    }
+
+    /// Send a request, retrying transient failures and rate limits per the
+    /// configured [`RetryPolicy`].
+    ///
+    /// Retries connection errors and `5xx`/`429` responses, sleeping with
+    /// exponential backoff. A `429 Too Many Requests` honours the `Retry-After`
+    /// header (seconds or HTTP-date) in place of the computed backoff. The last
+    /// error or response is returned once retries are exhausted.
+    async fn send_with_retry(
+        &self,
+        builder: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, UserServiceError> {
+        let policy = &self.retry_policy;
+        let mut attempt = 0;
+        loop {
+            // Clone the request so we can replay it; bodies that can't be
+            // cloned (e.g. streams) are sent once without retry.
+            let this_attempt = match builder.try_clone() {
+                Some(b) => b,
+                None => return builder.send().await.map_err(UserServiceError::Transport),
+            };
+
+            match this_attempt.send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status.is_server_error();
+                    if retryable && attempt < policy.max_retries {
+                        let delay = if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                            parse_retry_after(&response).unwrap_or_else(|| policy.backoff(attempt))
+                        } else {
+                            policy.backoff(attempt)
+                        };
+                        info!(
+                            "Retrying after status {} (attempt {}/{}), sleeping {:?}",
+                            status, attempt + 1, policy.max_retries, delay
+                        );
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                        continue;
+                    }
+                    return Ok(response);
+                }
+                Err(err) => {
+                    let retryable = err.is_connect() || err.is_timeout() || err.is_request();
+                    if retryable && attempt < policy.max_retries {
+                        let delay = policy.backoff(attempt);
+                        info!(
+                            "Retrying after transport error (attempt {}/{}), sleeping {:?}: {}",
+                            attempt + 1, policy.max_retries, delay, err
+                        );
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                        continue;
+                    }
+                    return Err(UserServiceError::Transport(err));
+                }
+            }
+        }
+    }
+}
+
+/// Builder for [`UserService`], used to customise the `User-Agent` and any
+/// default headers applied to every request before the [`Client`] is built.
+pub struct UserServiceBuilder {
+    api_url: Option<String>,
+    user_agent: String,
+    default_headers: HeaderMap,
+    credential: Option<Credential>,
+    retry_policy: RetryPolicy,
+    root_certificates: Vec<reqwest::Certificate>,
+}
+
+impl UserServiceBuilder {
+    fn new() -> Self {
+        UserServiceBuilder {
+            api_url: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            default_headers: HeaderMap::new(),
+            credential: None,
+            retry_policy: RetryPolicy::default(),
+            root_certificates: Vec::new(),
+        }
+    }
+
+    /// Override the retry policy applied to every request.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Authenticate with an `Authorization: Bearer <token>` header.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.credential = Some(Credential::Bearer(token));
+        self
+    }
+
+    /// Authenticate with a custom API-key header.
+    pub fn with_api_key(mut self, header_name: &str, value: &str) -> Self {
+        self.credential = Some(Credential::ApiKey {
+            header: header_name.to_string(),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    /// Override the base API URL. Defaults to the `API_URL` environment
+    /// variable (or `https://api.example.com`) when left unset.
+    pub fn api_url(mut self, api_url: String) -> Self {
+        self.api_url = Some(api_url);
+        self
+    }
+
+    /// Set the `User-Agent` sent with every request.
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Trust an additional root certificate, for talking to internal APIs
+    /// served behind a private CA. Call repeatedly to add more than one.
+    pub fn add_root_certificate(mut self, certificate: reqwest::Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Trust an additional PEM-encoded root certificate. Malformed PEM is
+    /// ignored so a bad cert can't panic client construction.
+    pub fn add_root_certificate_pem(mut self, pem: &[u8]) -> Self {
+        if let Ok(certificate) = reqwest::Certificate::from_pem(pem) {
+            self.root_certificates.push(certificate);
+        }
+        self
+    }
+
+    /// Add a default header applied to every request (e.g. `Accept` or a
+    /// custom tracing id). Invalid header names or values are ignored.
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        if let (Ok(name), Ok(value)) =
+            (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value))
+        {
+            self.default_headers.insert(name, value);
+        }
+        self
+    }
+
+    /// Build the configured [`UserService`].
+    pub fn build(mut self) -> UserService {
+        let api_url = self.api_url.take().unwrap_or_else(|| {
+            env::var("API_URL").unwrap_or_else(|_| "https://api.example.com".to_string())
+        });
+
+        // Fold the credential into the default headers so it rides every request.
+        if let Some(credential) = self.credential.take() {
+            match credential {
+                Credential::Bearer(token) => {
+                    if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", token)) {
+                        self.default_headers.insert(reqwest::header::AUTHORIZATION, value);
+                    }
+                }
+                Credential::ApiKey { header, value } => {
+                    if let (Ok(name), Ok(value)) =
+                        (HeaderName::from_bytes(header.as_bytes()), HeaderValue::from_str(&value))
+                    {
+                        self.default_headers.insert(name, value);
+                    }
+                }
+            }
+        }
+
+        let mut client_builder = Client::builder()
+            .user_agent(self.user_agent)
+            .default_headers(self.default_headers);
+
+        // Select the TLS backend at build time. The features are mutually
+        // exclusive in practice; `rustls-tls` wins if both are enabled.
+        #[cfg(feature = "rustls-tls")]
+        {
+            client_builder = client_builder.use_rustls_tls();
+        }
+        #[cfg(all(feature = "native-tls", not(feature = "rustls-tls")))]
+        {
+            client_builder = client_builder.use_native_tls();
+        }
+
+        for certificate in self.root_certificates {
+            client_builder = client_builder.add_root_certificate(certificate);
+        }
+
+        let client = client_builder
+            .build()
+            .unwrap_or_else(|err| {
+                error!("Failed to build HTTP client, falling back to default: {}", err);
+                Client::new()
+            });
+
+        let mut service = UserService::with_client(client, api_url);
+        service.retry_policy = self.retry_policy;
+        service
+    }
+}
+
+/// A lazy, page-at-a-time stream of [`User`] items.
+///
+/// The stream buffers the current page and remembers where the next page lives.
+/// Each [`next`](UserStream::next) pops one user from the buffer and, once the
+/// buffer drains, fetches the next page. Iteration ends at an empty page, or at
+/// a page with no `next` link that holds fewer than `per_page` rows. Pages are
+/// fetched only as items are pulled, so a caller that stops after `n` items
+/// does not fetch further pages. Items are pulled with the async
+/// [`next`](UserStream::next) method; the type implements neither `Iterator`
+/// nor `Stream`, so adapters such as `.take(n)` are not available on it.
+pub struct UserStream<'a> {
+    service: &'a UserService,
+    buffer: std::collections::VecDeque<User>,
+    /// The `rel="next"` URL advertised by the last fetched page, or `None`
+    /// before the first fetch and whenever that page carried no such link.
+    next_url: Option<String>,
+    per_page: u32,
+    page: u32,
+    /// Whether the first page has been requested yet.
+    primed: bool,
+    /// Set once a fetched page signals the end of iteration.
+    exhausted: bool,
+}
+
+impl<'a> UserStream<'a> {
+    fn new(service: &'a UserService, per_page: u32) -> Self {
+        UserStream {
+            service,
+            buffer: std::collections::VecDeque::new(),
+            next_url: None,
+            per_page,
+            page: 1,
+            primed: false,
+            exhausted: false,
+        }
+    }
+
+    /// Yield the next user, fetching another page when the local buffer empties.
+    ///
+    /// Returns `Ok(None)` once every page has been consumed.
+    pub async fn next(&mut self) -> Result<Option<User>, UserServiceError> {
+        loop {
+            if let Some(user) = self.buffer.pop_front() {
+                return Ok(Some(user));
+            }
+            if self.exhausted {
+                return Ok(None);
+            }
+            if !self.fetch_next_page().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Fetch the next page into the buffer. Returns `false` when there is no
+    /// further page to fetch (the stream is finished).
+    async fn fetch_next_page(&mut self) -> Result<bool, UserServiceError> {
+        let url = match (self.primed, self.next_url.take()) {
+            // First fetch: start at page 1 of the users collection.
+            (false, _) => format!(
+                "{}/users?page={}&per_page={}",
+                self.service.api_url, self.page, self.per_page
+            ),
+            // A `Link: rel="next"` URL was advertised by the previous page.
+            (true, Some(next)) => next,
+            // No advertised next link: fall back to the page counter.
+            (true, None) => format!(
+                "{}/users?page={}&per_page={}",
+                self.service.api_url, self.page, self.per_page
+            ),
+        };
+        self.primed = true;
+
+        info!("Fetching users page: {}", url);
+        let response = self.service.send_with_retry(self.service.client.get(&url)).await?;
+        let response = check_status(response)?;
+
+        self.next_url = parse_next_link(&response);
+        let users = response.json::<Vec<User>>()
+            .await
+            .map_err(UserServiceError::Deserialize)?;
+
+        // An empty page terminates iteration even if a stale next link exists.
+        if users.is_empty() {
+            self.exhausted = true;
+            return Ok(false);
+        }
+
+        self.page += 1;
+        if self.next_url.is_none() {
+            // Without a Link header we keep paging until we see a short page.
+            if (users.len() as u32) < self.per_page {
+                self.exhausted = true;
+            }
+        }
+
+        self.buffer.extend(users);
+        Ok(true)
+    }
+}
+
+/// Map a response's status code onto a [`UserServiceError`], passing through
+/// the response unchanged on success.
+fn check_status(response: reqwest::Response) -> Result<reqwest::Response, UserServiceError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    error!("Request failed with status {}", status);
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+            Err(UserServiceError::Unauthorized)
+        }
+        reqwest::StatusCode::NOT_FOUND => Err(UserServiceError::NotFound),
+        s => Err(UserServiceError::Server(s)),
+    }
+}
+
+/// Parse a `Retry-After` header into a delay. Accepts either a number of
+/// seconds or an HTTP-date, returning `None` when the header is absent or
+/// unparseable so the caller can fall back to the computed backoff.
+fn parse_retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let header = response.headers().get(reqwest::header::RETRY_AFTER)?;
+    let header = header.to_str().ok()?.trim();
+
+    if let Ok(secs) = header.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // Otherwise it should be an HTTP-date; the delay is the gap until then.
+    let when = httpdate::parse_http_date(header).ok()?;
+    when.duration_since(SystemTime::now()).ok()
+}
+
+/// A pseudo-random jitter in `[0, ceiling)`, derived from the wall clock so we
+/// avoid pulling in a random-number dependency for a best-effort spread.
+fn jitter_up_to(ceiling: Duration) -> Duration {
+    let ceiling_nanos = ceiling.as_nanos();
+    if ceiling_nanos == 0 {
+        return Duration::ZERO;
+    }
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u128)
+        .unwrap_or(0);
+    let nanos = seed % ceiling_nanos;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Extract the `rel="next"` URL from a response's `Link` header, if any.
+///
+/// Parses the comma-separated RFC 5988 form
+/// `<https://api.example.com/users?page=2>; rel="next"`.
+fn parse_next_link(response: &reqwest::Response) -> Option<String> {
+    let header = response.headers().get(reqwest::header::LINK)?;
+    let header = header.to_str().ok()?;
+
+    for part in header.split(',') {
+        let mut segments = part.split(';');
+        let url_segment = segments.next()?.trim();
+        let is_next = segments.any(|s| {
+            let s = s.trim();
+            s == "rel=\"next\"" || s == "rel=next"
+        });
+        if is_next {
+            let url = url_segment.trim_start_matches('<').trim_end_matches('>');
+            return Some(url.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::prelude::*;
+
+    /// Build a service pointed at a local mock server via the injection seam.
+    fn service_for(server: &MockServer) -> UserService {
+        UserService::with_client(Client::new(), server.base_url())
+    }
+
+    #[tokio::test]
+    async fn get_users_parses_json_array() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/users");
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(serde_json::json!([
+                    { "id": 1, "name": "Ada", "email": "ada@example.com" },
+                    { "id": 2, "name": "Grace", "email": "grace@example.com" },
+                ]));
+        });
+
+        let users = service_for(&server).get_users().await.unwrap();
+
+        mock.assert();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].name, "Ada");
+    }
+
+    #[tokio::test]
+    async fn get_users_handles_empty_body() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/users");
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(serde_json::json!([]));
+        });
+
+        let users = service_for(&server).get_users().await.unwrap();
+
+        assert!(users.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_users_surfaces_non_2xx_status() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/users");
+            then.status(404);
+        });
+
+        let err = service_for(&server).get_users().await.unwrap_err();
+
+        assert!(matches!(err, UserServiceError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn get_users_sends_expected_path_and_headers() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/users")
+                .header("authorization", "Bearer secret-token");
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(serde_json::json!([]));
+        });
+
+        let service = UserService::builder()
+            .api_url(server.base_url())
+            .with_token("secret-token".to_string())
+            .build();
+        service.get_users().await.unwrap();
+
+        mock.assert();
+    }
+}